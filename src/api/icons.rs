@@ -1,41 +1,206 @@
 use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
 use std::fs::{create_dir_all, remove_file, symlink_metadata, File};
 use std::io::prelude::*;
-use std::net::ToSocketAddrs;
-use std::time::{Duration, SystemTime};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use rocket::http::ContentType;
-use rocket::response::Content;
+use rocket::response::{Content, Redirect};
 use rocket::Route;
 
-use reqwest::{Url, header::HeaderMap, blocking::Client, blocking::Response};
-
-use rocket::http::Cookie;
+use reqwest::{
+    cookie::{CookieStore, Jar},
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::{HeaderMap, HeaderValue},
+    blocking::Client, blocking::Response,
+    Url,
+};
 
+use html5gum::{Emitter, Tokenizer};
 use regex::Regex;
-use soup::prelude::*;
 
 use crate::error::Error;
 use crate::CONFIG;
 use crate::util::Cached;
 
 pub fn routes() -> Vec<Route> {
-    routes![icon]
+    if CONFIG.icon_service() == "internal" {
+        routes![icon_internal]
+    } else {
+        routes![icon_external]
+    }
 }
 
 const FALLBACK_ICON: &[u8; 344] = include_bytes!("../static/fallback-icon.png");
 
 const ALLOWED_CHARS: &str = "_-.";
 
+// Rebuilding a whole reqwest::blocking::Client (its own connection pool and background runtime
+// thread) on every icon fetch is wasteful once several favicon downloads are happening
+// concurrently, e.g. an org dashboard loading icons for many domains at once. Build it once and
+// share it; the cookie jar below is what actually needs to be bounded, not the client.
 static CLIENT: Lazy<Client> = Lazy::new(|| {
-    // Reuse the client between requests
     Client::builder()
         .timeout(Duration::from_secs(CONFIG.icon_download_timeout()))
         .default_headers(_header_map())
+        // Track cookies across the whole lifetime of a fetch (page fetch, https->http fallback,
+        // redirects, and the final icon download), instead of manually scraping and replaying a
+        // `set-cookie` string for a single origin. Scoped per host and evicted after a period of
+        // disuse so this doesn't grow without bound over the life of the process.
+        .cookie_provider(Arc::new(EvictingCookieStore::new()))
+        // Check every redirect hop against the regex blacklist before following it, so a
+        // first-party page can't bounce us to a blacklisted host via a 30x response. This is
+        // deliberately the regex-only half of the blacklist check: this closure isn't async and
+        // runs inline on reqwest's runtime, so doing the global-IP check's blocking DNS lookup
+        // here would stall every other concurrent favicon fetch. The global-IP check still
+        // happens for this hop, just a moment later in GlobalOnlyResolver, which does that
+        // lookup via spawn_blocking before we ever connect to the redirected-to host.
+        .redirect(reqwest::redirect::Policy::custom(|attempt| {
+            let host = attempt.url().host_str().unwrap_or_default();
+            if is_domain_blacklisted_by_regex(host) {
+                attempt.stop()
+            } else {
+                attempt.follow()
+            }
+        }))
+        // Resolve every hostname ourselves and hand reqwest the already-validated addresses,
+        // so the address we connect to is guaranteed to be the one we checked (no DNS
+        // rebinding gap between the blacklist check above and the actual TCP connect).
+        .dns_resolver(Arc::new(GlobalOnlyResolver))
         .build()
         .unwrap()
 });
 
+// How long a host's cookies are kept around without being touched before they're evicted.
+const COOKIE_JAR_TTL: Duration = Duration::from_secs(60);
+
+/// A `reqwest` cookie store that keeps a separate jar per host and evicts any host that hasn't
+/// been read from or written to in a while, so a long-lived shared client's cookies don't
+/// accumulate forever across every distinct domain it's ever fetched a favicon for.
+struct EvictingCookieStore {
+    jars: Mutex<HashMap<String, (Jar, Instant)>>,
+}
+
+impl EvictingCookieStore {
+    fn new() -> Self {
+        Self { jars: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl CookieStore for EvictingCookieStore {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let host = url.host_str().unwrap_or_default().to_string();
+        let now = Instant::now();
+
+        let mut jars = self.jars.lock().unwrap();
+        jars.retain(|_, (_, last_used)| now.duration_since(*last_used) < COOKIE_JAR_TTL);
+
+        let (jar, last_used) = jars.entry(host).or_insert_with(|| (Jar::default(), now));
+        *last_used = now;
+        jar.set_cookies(cookie_headers, url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let host = url.host_str().unwrap_or_default().to_string();
+        let mut jars = self.jars.lock().unwrap();
+
+        let (jar, last_used) = jars.get_mut(&host)?;
+        *last_used = Instant::now();
+        jar.cookies(url)
+    }
+}
+
+// How long a resolved address is trusted before we resolve the host again. The redirect
+// policy and DNS resolver installed on CLIENT run on reqwest's own internal runtime threads,
+// not on the thread handling a given icon request, so a thread-local cache cleared by
+// download_icon never actually reaches them. A small shared, time-bounded cache gives the
+// "don't re-resolve the same host several times while handling one icon fetch" win those hops
+// need without caching (or growing) forever.
+const RESOLVE_CACHE_TTL: Duration = Duration::from_secs(10);
+
+struct ResolveCacheEntry {
+    addrs: Vec<SocketAddr>,
+    inserted_at: Instant,
+}
+
+static RESOLVE_CACHE: Lazy<Mutex<HashMap<String, ResolveCacheEntry>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn resolve_host(host: &str) -> std::io::Result<Vec<SocketAddr>> {
+    let now = Instant::now();
+
+    {
+        let cache = RESOLVE_CACHE.lock().unwrap();
+        if let Some(entry) = cache.get(host) {
+            if now.duration_since(entry.inserted_at) < RESOLVE_CACHE_TTL {
+                return Ok(entry.addrs.clone());
+            }
+        }
+    }
+
+    let addrs: Vec<SocketAddr> = (host, 0).to_socket_addrs()?.collect();
+
+    let mut cache = RESOLVE_CACHE.lock().unwrap();
+    // Opportunistically evict expired entries so this doesn't grow without bound over the
+    // life of the process.
+    cache.retain(|_, entry| now.duration_since(entry.inserted_at) < RESOLVE_CACHE_TTL);
+    cache.insert(host.to_string(), ResolveCacheEntry { addrs: addrs.clone(), inserted_at: now });
+
+    Ok(addrs)
+}
+
+/// Resolves a hostname and rejects it if *any* resolved address is non-global (private,
+/// loopback, link-local, unspecified or documentation ranges), to stop SSRF via a domain
+/// whose A/AAAA records point at internal infrastructure.
+fn host_resolves_to_only_global_ips(host: &str) -> bool {
+    match resolve_host(host) {
+        Ok(addrs) => addrs.iter().all(|addr| {
+            if addr.ip().is_global() {
+                true
+            } else {
+                warn!("IP {} for domain '{}' is not a global IP!", addr.ip(), host);
+                false
+            }
+        }),
+        Err(_) => false,
+    }
+}
+
+/// A `reqwest` DNS resolver that, when `icon_blacklist_non_global_ips` is enabled, refuses to
+/// hand back non-global addresses (otherwise it returns every resolved address), used so that the
+/// connection reqwest actually opens is to an address we've validated ourselves, rather than
+/// trusting a second, independent resolution done internally by the HTTP client.
+struct GlobalOnlyResolver;
+
+impl Resolve for GlobalOnlyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+
+        Box::pin(async move {
+            // resolve_host() does a blocking getaddrinfo() syscall; run it on a blocking-pool
+            // thread instead of inline so we don't stall the async runtime that's driving
+            // every other concurrent favicon fetch while we wait on it.
+            let lookup_host = host.clone();
+            let addrs = tokio::task::spawn_blocking(move || resolve_host(&lookup_host))
+                .await
+                .map_err(|e| -> Box<dyn std::error::Error + Send + Sync> { Box::new(e) })?
+                .map_err(|e| -> Box<dyn std::error::Error + Send + Sync> { Box::new(e) })?;
+
+            if CONFIG.icon_blacklist_non_global_ips() {
+                for addr in &addrs {
+                    if !addr.ip().is_global() {
+                        warn!("IP {} for host '{}' is not a global IP!", addr.ip(), host);
+                        return Err("Resolved IP is not a global IP".into());
+                    }
+                }
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
 fn is_valid_domain(domain: &str) -> bool {
     // Don't allow empty or too big domains or path traversal
     if domain.is_empty() || domain.len() > 255 || domain.contains("..") {
@@ -53,44 +218,68 @@ fn is_valid_domain(domain: &str) -> bool {
 }
 
 #[get("/<domain>/icon.png")]
-fn icon(domain: String) -> Cached<Content<Vec<u8>>> {
-    let icon_type = ContentType::new("image", "x-icon");
+fn icon_internal(domain: String) -> Cached<Content<Vec<u8>>> {
+    if !is_valid_domain(&domain) {
+        warn!("Invalid domain: {:#?}", domain);
+        return Cached::long(Content(ContentType::new("image", "x-icon"), FALLBACK_ICON.to_vec()));
+    }
+
+    let icon = get_icon(&domain);
+    let icon_type = get_icon_type(&icon).unwrap_or("x-icon");
+    Cached::long(Content(ContentType::new("image", icon_type), icon))
+}
 
+/// Sniffs the leading magic bytes of a downloaded icon to determine its real image type,
+/// since servers and `link rel=icon` hrefs both lie about this frequently.
+/// Returns `None` when the payload doesn't match any of the raster image types we accept,
+/// which notably rejects SVG/XML (and anything else) rather than guessing.
+fn get_icon_type(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, 0x50, 0x4E, 0x47, ..] => Some("png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("jpeg"),
+        [0x47, 0x49, 0x46, 0x38, ..] => Some("gif"),
+        [0x52, 0x49, 0x46, 0x46, _, _, _, _, 0x57, 0x45, 0x42, 0x50, ..] => Some("webp"),
+        [0x00, 0x00, 0x01, 0x00, ..] => Some("x-icon"),
+        _ => None,
+    }
+}
+
+// When the `icon_service` config option points to an external service instead of "internal",
+// we don't download or cache anything ourselves; we just send the client over there with the
+// domain filled in, so the external service can do the actual crawling/caching.
+#[get("/<domain>/icon.png")]
+fn icon_external(domain: String) -> Option<Cached<Redirect>> {
     if !is_valid_domain(&domain) {
         warn!("Invalid domain: {:#?}", domain);
-        return Cached::long(Content(icon_type, FALLBACK_ICON.to_vec()));
+        return None;
+    }
+
+    if check_icon_domain_is_blacklisted(&domain) {
+        return None;
     }
 
-    Cached::long(Content(icon_type, get_icon(&domain)))
+    let url = CONFIG.icon_service().replace("{}", &domain);
+    Some(Cached::long(Redirect::to(url)))
 }
 
 fn check_icon_domain_is_blacklisted(domain: &str) -> bool {
-    let mut is_blacklisted = CONFIG.icon_blacklist_non_global_ips()
-        && (domain, 0)
-            .to_socket_addrs()
-            .map(|x| {
-                for ip_port in x {
-                    if !ip_port.ip().is_global() {
-                        warn!("IP {} for domain '{}' is not a global IP!", ip_port.ip(), domain);
-                        return true;
-                    }
-                }
-                false
-            })
-            .unwrap_or(false);
-
-    // Skip the regex check if the previous one is true already
-    if !is_blacklisted {
-        if let Some(blacklist) = CONFIG.icon_blacklist_regex() {
-            let regex = Regex::new(&blacklist).expect("Valid Regex");
-            if regex.is_match(&domain) {
-                warn!("Blacklisted domain: {:#?} matched {:#?}", domain, blacklist);
-                is_blacklisted = true;
-            }
+    (CONFIG.icon_blacklist_non_global_ips() && !host_resolves_to_only_global_ips(domain))
+        || is_domain_blacklisted_by_regex(domain)
+}
+
+/// The no-DNS half of `check_icon_domain_is_blacklisted`. Safe to call from a synchronous,
+/// non-async context (like the redirect policy closure on `CLIENT`) since, unlike the
+/// global-IP check, it never blocks on a DNS lookup.
+fn is_domain_blacklisted_by_regex(domain: &str) -> bool {
+    if let Some(blacklist) = CONFIG.icon_blacklist_regex() {
+        let regex = Regex::new(&blacklist).expect("Valid Regex");
+        if regex.is_match(domain) {
+            warn!("Blacklisted domain: {:#?} matched {:#?}", domain, blacklist);
+            return true;
         }
     }
 
-    is_blacklisted
+    false
 }
 
 fn get_icon(domain: &str) -> Vec<u8> {
@@ -187,8 +376,8 @@ impl Icon {
     }
 }
 
-/// Returns a Result/Tuple which holds a Vector IconList and a string which holds the cookies from the last response.
-/// There will always be a result with a string which will contain https://example.com/favicon.ico and an empty string for the cookies.
+/// Returns a Result which holds a Vector IconList.
+/// There will always be a result with https://example.com/favicon.ico.
 /// This does not mean that that location does exists, but it is the default location browser use.
 ///
 /// # Argument
@@ -196,10 +385,10 @@ impl Icon {
 ///
 /// # Example
 /// ```
-/// let (mut iconlist, cookie_str) = get_icon_url("github.com")?;
-/// let (mut iconlist, cookie_str) = get_icon_url("gitlab.com")?;
+/// let mut iconlist = get_icon_url("github.com")?;
+/// let mut iconlist = get_icon_url("gitlab.com")?;
 /// ```
-fn get_icon_url(domain: &str) -> Result<(Vec<Icon>, String), Error> {
+fn get_icon_url(domain: &str) -> Result<Vec<Icon>, Error> {
     // Default URL with secure and insecure schemes
     let ssldomain = format!("https://{}", domain);
     let httpdomain = format!("http://{}", domain);
@@ -207,53 +396,27 @@ fn get_icon_url(domain: &str) -> Result<(Vec<Icon>, String), Error> {
     // Create the iconlist
     let mut iconlist: Vec<Icon> = Vec::new();
 
-    // Create the cookie_str to fill it all the cookies from the response
-    // These cookies can be used to request/download the favicon image.
-    // Some sites have extra security in place with for example XSRF Tokens.
-    let mut cookie_str = String::new();
-
     let resp = get_page(&ssldomain).or_else(|_| get_page(&httpdomain));
     if let Ok(content) = resp {
         // Extract the URL from the respose in case redirects occured (like @ gitlab.com)
         let url = content.url().clone();
 
-        let raw_cookies = content.headers().get_all("set-cookie");
-        cookie_str = raw_cookies
-            .iter()
-            .filter_map(|raw_cookie| raw_cookie.to_str().ok())
-            .map(|cookie_str| {
-                if let Ok(cookie) = Cookie::parse(cookie_str) {
-                    format!("{}={}; ", cookie.name(), cookie.value())
-                } else {
-                    String::new()
-                }
-            })
-            .collect::<String>();
+        // Any cookies the site set (e.g. session or anti-CSRF cookies on a redirected
+        // subdomain) are captured by the shared client's cookie jar and will automatically be
+        // replayed when we download the actual favicon below from that same host, no manual
+        // threading needed. The jar is keyed by exact host, so they aren't sent to other hosts.
 
         // Add the default favicon.ico to the list with the domain the content responded from.
         iconlist.push(Icon::new(35, url.join("/favicon.ico").unwrap().into_string()));
 
         // 512KB should be more than enough for the HTML, though as we only really need
-        // the HTML header, it could potentially be reduced even further
+        // the HTML header, it could potentially be reduced even further. In practice the
+        // tokenizer below stops reading long before this limit is hit, since it bails out
+        // as soon as the header ends.
         let limited_reader = content.take(512 * 1024);
 
-        let soup = Soup::from_reader(limited_reader)?;
-        // Search for and filter
-        let favicons = soup
-            .tag("link")
-            .attr("rel", Regex::new(r"icon$|apple.*icon")?) // Only use icon rels
-            .attr("href", Regex::new(r"(?i)\w+\.(jpg|jpeg|png|ico)(\?.*)?$|^data:image.*base64")?) // Only allow specific extensions
-            .find_all();
-
-        // Loop through all the found icons and determine it's priority
-        for favicon in favicons {
-            let sizes = favicon.get("sizes");
-            let href = favicon.get("href").expect("Missing href");
-            let full_href = url.join(&href).unwrap().into_string();
-
-            let priority = get_icon_priority(&full_href, sizes);
-
-            iconlist.push(Icon::new(priority, full_href))
+        for icon in parse_favicons(limited_reader, &url)? {
+            iconlist.push(icon);
         }
     } else {
         // Add the default favicon.ico to the list with just the given domain
@@ -265,28 +428,183 @@ fn get_icon_url(domain: &str) -> Result<(Vec<Icon>, String), Error> {
     iconlist.sort_by_key(|x| x.priority);
 
     // There always is an icon in the list, so no need to check if it exists, and just return the first one
-    Ok((iconlist, cookie_str))
+    Ok(iconlist)
 }
 
-fn get_page(url: &str) -> Result<Response, Error> {
-    get_page_with_cookies(url, "")
+/// Streams `<link rel=icon>` tags out of an HTML header without building a DOM.
+/// Parsing aborts as soon as `</head>` (or a stray `<body>`) is seen, so for most pages
+/// we never read the rest of the response body off the network at all.
+fn parse_favicons(reader: impl Read, url: &Url) -> Result<Vec<Icon>, Error> {
+    let rel_regex = Regex::new(r"icon$|apple.*icon")?; // Only use icon rels
+    let href_regex = Regex::new(r"(?i)\w+\.(jpg|jpeg|png|ico)(\?.*)?$|^data:image.*base64")?; // Only allow specific extensions
+
+    let mut icons = Vec::new();
+
+    for token in Tokenizer::new_with_emitter(reader, IconEmitter::default()) {
+        match token {
+            IconToken::HeadEnd => break,
+            IconToken::Link { rel, href, sizes } => {
+                if !rel_regex.is_match(&rel) || !href_regex.is_match(&href) {
+                    continue;
+                }
+
+                let full_href = match url.join(&href) {
+                    Ok(full_href) => full_href.into_string(),
+                    Err(_) => continue,
+                };
+
+                let priority = get_icon_priority(&full_href, sizes);
+                icons.push(Icon::new(priority, full_href));
+            }
+        }
+    }
+
+    Ok(icons)
+}
+
+enum IconToken {
+    Link { rel: String, href: String, sizes: Option<String> },
+    HeadEnd,
+}
+
+/// A minimal `html5gum::Emitter` that only cares about `<link>` tags and about knowing
+/// when the document header is over. Text, comments and doctypes are dropped; every other
+/// tag's name and attributes are buffered only until the tag ends, and no token is queued for it.
+#[derive(Default)]
+struct IconEmitter {
+    queue: VecDeque<IconToken>,
+    current_tag_name: Vec<u8>,
+    current_tag_is_end: bool,
+    current_attribute_name: Vec<u8>,
+    current_attribute_value: Vec<u8>,
+    current_attributes: Vec<(Vec<u8>, Vec<u8>)>,
+    last_start_tag: Vec<u8>,
+}
+
+impl IconEmitter {
+    fn flush_attribute(&mut self) {
+        if !self.current_attribute_name.is_empty() {
+            self.current_attributes.push((
+                std::mem::take(&mut self.current_attribute_name),
+                std::mem::take(&mut self.current_attribute_value),
+            ));
+        }
+    }
+}
+
+impl Emitter for IconEmitter {
+    type Token = IconToken;
+
+    fn set_last_start_tag(&mut self, last_start_tag: Option<&[u8]>) {
+        self.last_start_tag.clear();
+        if let Some(tag) = last_start_tag {
+            self.last_start_tag.extend_from_slice(tag);
+        }
+    }
+
+    fn emit_eof(&mut self) {}
+
+    fn emit_error(&mut self, _error: html5gum::Error) {}
+
+    fn pop_token(&mut self) -> Option<Self::Token> {
+        self.queue.pop_front()
+    }
+
+    fn emit_string(&mut self, _s: &[u8]) {}
+
+    fn init_start_tag(&mut self) {
+        self.current_tag_name.clear();
+        self.current_tag_is_end = false;
+        self.current_attributes.clear();
+        self.current_attribute_name.clear();
+        self.current_attribute_value.clear();
+    }
+
+    fn init_end_tag(&mut self) {
+        self.current_tag_name.clear();
+        self.current_tag_is_end = true;
+        self.current_attributes.clear();
+        self.current_attribute_name.clear();
+        self.current_attribute_value.clear();
+    }
+
+    fn init_comment(&mut self) {}
+
+    fn emit_current_tag(&mut self) -> Option<html5gum::State> {
+        self.flush_attribute();
+
+        let name = self.current_tag_name.to_ascii_lowercase();
+
+        if self.current_tag_is_end && name == b"head" {
+            self.queue.push_back(IconToken::HeadEnd);
+        } else if !self.current_tag_is_end && name == b"body" {
+            self.queue.push_back(IconToken::HeadEnd);
+        } else if !self.current_tag_is_end && name == b"link" {
+            let mut rel = None;
+            let mut href = None;
+            let mut sizes = None;
+
+            for (name, value) in self.current_attributes.drain(..) {
+                match name.to_ascii_lowercase().as_slice() {
+                    b"rel" => rel = Some(String::from_utf8_lossy(&value).into_owned()),
+                    b"href" => href = Some(String::from_utf8_lossy(&value).into_owned()),
+                    b"sizes" => sizes = Some(String::from_utf8_lossy(&value).into_owned()),
+                    _ => {}
+                }
+            }
+
+            if let (Some(rel), Some(href)) = (rel, href) {
+                self.queue.push_back(IconToken::Link { rel, href, sizes });
+            }
+        }
+
+        None
+    }
+
+    fn emit_current_comment(&mut self) {}
+    fn emit_current_doctype(&mut self) {}
+    fn set_self_closing(&mut self) {}
+    fn set_force_quirks(&mut self) {}
+
+    fn push_tag_name(&mut self, s: &[u8]) {
+        self.current_tag_name.extend_from_slice(s);
+    }
+
+    fn push_comment(&mut self, _s: &[u8]) {}
+    fn push_doctype_name(&mut self, _s: &[u8]) {}
+    fn init_doctype(&mut self) {}
+
+    fn init_attribute(&mut self) {
+        self.flush_attribute();
+    }
+
+    fn push_attribute_name(&mut self, s: &[u8]) {
+        self.current_attribute_name.extend_from_slice(s);
+    }
+
+    fn push_attribute_value(&mut self, s: &[u8]) {
+        self.current_attribute_value.extend_from_slice(s);
+    }
+
+    fn set_doctype_public_identifier(&mut self, _value: &[u8]) {}
+    fn set_doctype_system_identifier(&mut self, _value: &[u8]) {}
+    fn push_doctype_public_identifier(&mut self, _s: &[u8]) {}
+    fn push_doctype_system_identifier(&mut self, _s: &[u8]) {}
+
+    fn current_is_appropriate_end_tag_token(&mut self) -> bool {
+        self.current_tag_is_end && !self.last_start_tag.is_empty() && self.current_tag_name == self.last_start_tag
+    }
 }
 
-fn get_page_with_cookies(url: &str, cookie_str: &str) -> Result<Response, Error> {
+fn get_page(url: &str) -> Result<Response, Error> {
     if check_icon_domain_is_blacklisted(Url::parse(url).unwrap().host_str().unwrap_or_default()) {
         err!("Favicon rel linked to a non blacklisted domain!");
     }
 
-    if cookie_str.is_empty() {
-        CLIENT.get(url).send()?.error_for_status().map_err(Into::into)
-    } else {
-        CLIENT
-            .get(url)
-            .header("cookie", cookie_str)
-            .send()?
-            .error_for_status()
-            .map_err(Into::into)
-    }
+    // Cookies set on this (or any earlier redirected-to) origin are stored in the shared
+    // client's cookie jar and get replayed automatically, including on the download_icon call
+    // below.
+    CLIENT.get(url).send()?.error_for_status().map_err(Into::into)
 }
 
 /// Returns a Integer with the priority of the type of the icon which to prefer.
@@ -373,7 +691,7 @@ fn download_icon(domain: &str) -> Result<Vec<u8>, Error> {
         err!("Domain is blacklisted", domain)
     }
 
-    let (iconlist, cookie_str) = get_icon_url(&domain)?;
+    let iconlist = get_icon_url(domain)?;
 
     let mut buffer = Vec::new();
 
@@ -386,7 +704,8 @@ fn download_icon(domain: &str) -> Result<Vec<u8>, Error> {
             match datauri.decode_to_vec() {
                 Ok((body, _fragment)) => {
                     // Also check if the size is atleast 67 bytes, which seems to be the smallest png i could create
-                    if body.len() >= 67 {
+                    // and make sure the decoded bytes are actually a known raster image type.
+                    if body.len() >= 67 && get_icon_type(&body).is_some() {
                         buffer = body;
                         break;
                     }
@@ -394,11 +713,18 @@ fn download_icon(domain: &str) -> Result<Vec<u8>, Error> {
                 _ => warn!("data uri is invalid"),
             };
         } else {
-            match get_page_with_cookies(&icon.href, &cookie_str) {
+            match get_page(&icon.href) {
                 Ok(mut res) => {
-                    info!("Downloaded icon from {}", icon.href);
-                    res.copy_to(&mut buffer)?;
-                    break;
+                    let mut body = Vec::new();
+                    res.copy_to(&mut body)?;
+
+                    if get_icon_type(&body).is_some() {
+                        info!("Downloaded icon from {}", icon.href);
+                        buffer = body;
+                        break;
+                    }
+
+                    info!("Downloaded icon from {} has an unknown or unsupported image type", icon.href);
                 }
                 Err(_) => info!("Download failed for {}", icon.href),
             };
@@ -1,12 +1,14 @@
+use std::collections::HashMap;
+
 use diesel::prelude::*;
 use serde_json::Value;
 
 use crate::api::EmptyResult;
 use crate::db::schema::org_policies;
 use crate::db::DbConn;
-use crate::error::MapResult;
+use crate::error::{Error, MapResult};
 
-use super::Organization;
+use super::{Organization, UserOrgType};
 
 #[derive(Debug, Identifiable, Queryable, Insertable, Associations, AsChangeset)]
 #[table_name = "org_policies"]
@@ -108,8 +110,11 @@ impl OrgPolicy {
     pub fn find_by_user(user_uuid: &str, conn: &DbConn) -> Vec<Self> {
         use crate::db::schema::users_organizations;
 
+        // This needs to be an inner join, not a left join: the `user_uuid` condition belongs
+        // to the join itself, so a left join would still emit every org_policies row (with
+        // nulls on the users_organizations side) for orgs the user isn't even a member of.
         org_policies::table
-            .left_join(
+            .inner_join(
                 users_organizations::table.on(
                     users_organizations::org_uuid.eq(org_policies::org_uuid)
                         .and(users_organizations::user_uuid.eq(user_uuid)))
@@ -133,6 +138,35 @@ impl OrgPolicy {
             .map_res("Error deleting org_policy")
     }
 
+    /// Returns true if the user is a member (but not an owner/admin) of at least one
+    /// organization that has an enabled TwoFactorAuthentication policy. Owners/admins are
+    /// exempt so they can't lock themselves out of the org they'd need to manage the policy
+    /// from. This only reports whether the policy applies; it does not reject a login or
+    /// change the user's org membership itself.
+    ///
+    /// An error loading the user's memberships is returned to the caller rather than folded
+    /// into the result: a lookup failure isn't evidence the policy applies. The policy lookup
+    /// itself goes through `find_by_user`, which panics on a DB error.
+    pub fn is_2fa_required_for_user(user_uuid: &str, conn: &DbConn) -> Result<bool, Error> {
+        use crate::db::schema::users_organizations;
+
+        let member_atypes: HashMap<String, i32> = users_organizations::table
+            .filter(users_organizations::user_uuid.eq(user_uuid))
+            .select((users_organizations::org_uuid, users_organizations::atype))
+            .load::<(String, i32)>(&**conn)
+            .map_res("Error loading user organizations")?
+            .into_iter()
+            .collect();
+
+        let required = Self::find_by_user(user_uuid, conn).into_iter().any(|policy| {
+            policy.enabled
+                && policy.atype == OrgPolicyType::TwoFactorAuthentication as i32
+                && member_atypes.get(&policy.org_uuid).map(|&atype| atype > UserOrgType::Admin as i32).unwrap_or(false)
+        });
+
+        Ok(required)
+    }
+
     /*pub fn delete_all_by_user(user_uuid: &str, conn: &DbConn) -> EmptyResult {
         diesel::delete(twofactor::table.filter(twofactor::user_uuid.eq(user_uuid)))
             .execute(&**conn)